@@ -0,0 +1,50 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Paint-server resolution shared by `fill` and `stroke`.
+
+// external
+use cairo::{
+    self,
+    MatrixTrait,
+    PatternTrait,
+};
+
+// self
+use tree;
+use math::*;
+use traits::TransformFromBBox;
+use super::gradient;
+
+/// Resolves `ps` into a Cairo source pattern and sets it on `cr`.
+///
+/// Only covers paint-server variants that the rest of the fill/stroke
+/// dispatch can't build a pattern for itself, i.e. mesh gradients; callers
+/// are expected to fall through to their normal handling for everything
+/// else.
+pub fn set_source_paint_server(
+    cr: &cairo::Context,
+    ps: &tree::PaintServer,
+    bbox: Rect,
+) {
+    match *ps {
+        tree::PaintServer::MeshGradient(ref mesh) => {
+            let pattern = gradient::prepare_mesh(mesh);
+
+            if mesh.units_object_bbox {
+                pattern.set_matrix(invert(cairo::Matrix::from_bbox(bbox)));
+            }
+
+            cr.set_source(&pattern);
+        }
+        // Color/linear/radial/pattern paint servers are resolved by the
+        // rest of `fill`/`stroke`'s existing dispatch.
+        _ => {}
+    }
+}
+
+fn invert(mut m: cairo::Matrix) -> cairo::Matrix {
+    m.invert();
+    m
+}
@@ -0,0 +1,389 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! SVG filter-effects (`<filter>`) rendering.
+//!
+//! Primitives are evaluated in document order into named result buffers
+//! ("SourceGraphic", "SourceAlpha", and whatever a primitive's own `result`
+//! names), each buffer being a premultiplied ARgb32 pixel buffer the size of
+//! the filter region. The last primitive's buffer is copied back into the
+//! group's sub-surface, replacing its previous content.
+
+use std::collections::HashMap;
+use std::f64;
+
+// external
+use cairo;
+
+// self
+use tree;
+use math::*;
+
+const SOURCE_GRAPHIC: &'static str = "SourceGraphic";
+const SOURCE_ALPHA: &'static str = "SourceAlpha";
+
+#[derive(Clone)]
+struct Buffer {
+    data: Vec<u8>,
+    width: usize,
+    height: usize,
+    stride: usize,
+}
+
+impl Buffer {
+    fn blank(width: usize, height: usize, stride: usize) -> Self {
+        Buffer { data: vec![0u8; stride * height], width, height, stride }
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> [u8; 4] {
+        let idx = y * self.stride + x * 4;
+        [self.data[idx], self.data[idx + 1], self.data[idx + 2], self.data[idx + 3]]
+    }
+
+    fn set_pixel(&mut self, x: usize, y: usize, px: [u8; 4]) {
+        let idx = y * self.stride + x * 4;
+        self.data[idx..idx + 4].copy_from_slice(&px);
+    }
+}
+
+/// The filter region, in user space.
+///
+/// Defaults to -10%/-10%/120%/120% of `bbox`, per the SVG `<filter>` spec,
+/// unless `filter` carries its own `x`/`y`/`width`/`height` (via `filterUnits`
+/// of either `objectBoundingBox`, the default, or `userSpaceOnUse`).
+pub fn region(bbox: Rect, filter: &tree::Filter) -> Rect {
+    match filter.rect {
+        Some(r) if r.units_object_bbox => Rect::from_xywh(
+            bbox.x() + r.x * bbox.width(),
+            bbox.y() + r.y * bbox.height(),
+            r.width * bbox.width(),
+            r.height * bbox.height(),
+        ),
+        Some(r) => Rect::from_xywh(r.x, r.y, r.width, r.height),
+        None => {
+            let dx = bbox.width() * 0.1;
+            let dy = bbox.height() * 0.1;
+
+            Rect::from_xywh(
+                bbox.x() - dx,
+                bbox.y() - dy,
+                bbox.width() * 1.2,
+                bbox.height() * 1.2,
+            )
+        }
+    }
+}
+
+/// Applies `filter`'s primitive pipeline to `surface` in place.
+///
+/// `surface` must already be sized to cover `region(bbox)`, not just `bbox`
+/// itself, so primitives like blur/offset whose effect extends past the
+/// element's own bbox aren't hard-clipped at its edge.
+pub fn apply(
+    filter: &tree::Filter,
+    surface: &cairo::ImageSurface,
+    ts: &cairo::Matrix,
+) {
+    surface.flush();
+
+    let width = surface.get_width() as usize;
+    let height = surface.get_height() as usize;
+    let stride = surface.get_stride() as usize;
+
+    let source = Buffer {
+        data: surface.get_data().unwrap().to_vec(),
+        width, height, stride,
+    };
+
+    let mut alpha = source.clone();
+    for px in alpha.data.chunks_mut(4) {
+        px[0] = 0;
+        px[1] = 0;
+        px[2] = 0;
+        // px[3] (alpha) is left untouched.
+    }
+
+    let mut results: HashMap<String, Buffer> = HashMap::new();
+    results.insert(SOURCE_GRAPHIC.to_string(), source.clone());
+    results.insert(SOURCE_ALPHA.to_string(), alpha);
+
+    let mut last = source;
+
+    for primitive in &filter.primitives {
+        let input = results.get(primitive.input.as_str())
+            .cloned()
+            .unwrap_or_else(|| last.clone());
+
+        let output = match primitive.kind {
+            tree::FilterKind::GaussianBlur(ref fe) => {
+                // stdDeviation is a user-space length, same as feOffset's dx/dy,
+                // so it needs the same per-axis CTM scaling.
+                gaussian_blur(&input, fe.std_dev_x * ts.xx, fe.std_dev_y * ts.yy)
+            }
+            tree::FilterKind::Offset(ref fe) => {
+                offset(&input, to_px(fe.dx, ts.xx), to_px(fe.dy, ts.yy))
+            }
+            tree::FilterKind::Flood(ref fe) => {
+                flood(width, height, stride, fe.color, fe.opacity)
+            }
+            tree::FilterKind::Blend(ref fe) => {
+                let input2 = results.get(fe.input2.as_str()).cloned().unwrap_or_else(|| last.clone());
+                blend(&input, &input2, fe.mode)
+            }
+            tree::FilterKind::Composite(ref fe) => {
+                let input2 = results.get(fe.input2.as_str()).cloned().unwrap_or_else(|| last.clone());
+                composite(&input, &input2, fe.operator)
+            }
+        };
+
+        if let Some(ref name) = primitive.result {
+            results.insert(name.clone(), output.clone());
+        }
+
+        last = output;
+    }
+
+    let mut data = surface.get_data().unwrap();
+    data.copy_from_slice(&last.data);
+}
+
+fn to_px(v: f64, scale: f64) -> i32 {
+    (v * scale).round() as i32
+}
+
+/// Three-pass box blur approximation of a Gaussian, per the SVG spec formula:
+/// `d = floor(stdDev*3*sqrt(2*PI)/4 + 0.5)`.
+fn gaussian_blur(input: &Buffer, std_dev_x: f64, std_dev_y: f64) -> Buffer {
+    let mut buf = input.clone();
+
+    let dx = box_size(std_dev_x);
+    let dy = box_size(std_dev_y);
+
+    for _ in 0..3 {
+        buf = box_blur_horizontal(&buf, dx);
+        buf = box_blur_vertical(&buf, dy);
+    }
+
+    buf
+}
+
+fn box_size(std_dev: f64) -> usize {
+    if std_dev <= 0.0 {
+        return 0;
+    }
+
+    (std_dev * 3.0 * (2.0 * f64::consts::PI).sqrt() / 4.0 + 0.5).floor() as usize
+}
+
+fn box_blur_horizontal(input: &Buffer, d: usize) -> Buffer {
+    if d == 0 {
+        return input.clone();
+    }
+
+    let r = (d / 2) as i64;
+    let mut out = Buffer::blank(input.width, input.height, input.stride);
+
+    for y in 0..input.height {
+        for x in 0..input.width {
+            let mut sum = [0u32; 4];
+            let mut count = 0u32;
+
+            for ox in -r..=r {
+                let sx = x as i64 + ox;
+                if sx >= 0 && (sx as usize) < input.width {
+                    let px = input.pixel(sx as usize, y);
+                    for c in 0..4 {
+                        sum[c] += px[c] as u32;
+                    }
+                    count += 1;
+                }
+            }
+
+            let px = [
+                (sum[0] / count.max(1)) as u8,
+                (sum[1] / count.max(1)) as u8,
+                (sum[2] / count.max(1)) as u8,
+                (sum[3] / count.max(1)) as u8,
+            ];
+            out.set_pixel(x, y, px);
+        }
+    }
+
+    out
+}
+
+fn box_blur_vertical(input: &Buffer, d: usize) -> Buffer {
+    if d == 0 {
+        return input.clone();
+    }
+
+    let r = (d / 2) as i64;
+    let mut out = Buffer::blank(input.width, input.height, input.stride);
+
+    for x in 0..input.width {
+        for y in 0..input.height {
+            let mut sum = [0u32; 4];
+            let mut count = 0u32;
+
+            for oy in -r..=r {
+                let sy = y as i64 + oy;
+                if sy >= 0 && (sy as usize) < input.height {
+                    let px = input.pixel(x, sy as usize);
+                    for c in 0..4 {
+                        sum[c] += px[c] as u32;
+                    }
+                    count += 1;
+                }
+            }
+
+            let px = [
+                (sum[0] / count.max(1)) as u8,
+                (sum[1] / count.max(1)) as u8,
+                (sum[2] / count.max(1)) as u8,
+                (sum[3] / count.max(1)) as u8,
+            ];
+            out.set_pixel(x, y, px);
+        }
+    }
+
+    out
+}
+
+fn offset(input: &Buffer, dx: i32, dy: i32) -> Buffer {
+    let mut out = Buffer::blank(input.width, input.height, input.stride);
+
+    for y in 0..input.height {
+        for x in 0..input.width {
+            let sx = x as i64 - dx as i64;
+            let sy = y as i64 - dy as i64;
+            if sx >= 0 && sy >= 0 && (sx as usize) < input.width && (sy as usize) < input.height {
+                let px = input.pixel(sx as usize, sy as usize);
+                out.set_pixel(x, y, px);
+            }
+        }
+    }
+
+    out
+}
+
+fn flood(width: usize, height: usize, stride: usize, color: tree::Color, opacity: f64) -> Buffer {
+    let a = (255.0 * opacity).round().max(0.0).min(255.0) as u8;
+    // Premultiplied BGRA, matching Cairo's ARgb32 layout.
+    let px = [
+        mul_u8(color.blue, a),
+        mul_u8(color.green, a),
+        mul_u8(color.red, a),
+        a,
+    ];
+
+    let mut buf = Buffer::blank(width, height, stride);
+    for y in 0..height {
+        for x in 0..width {
+            buf.set_pixel(x, y, px);
+        }
+    }
+
+    buf
+}
+
+fn mul_u8(c: u8, a: u8) -> u8 {
+    ((c as u32 * a as u32) / 255) as u8
+}
+
+/// `in1` is `feBlend`'s `in` (painted on top), `in2` is its `in2`
+/// (the background underneath).
+fn blend(in1: &Buffer, in2: &Buffer, mode: tree::BlendMode) -> Buffer {
+    let mut out = Buffer::blank(in1.width, in1.height, in1.stride);
+
+    for y in 0..in1.height {
+        for x in 0..in1.width {
+            let a = in1.pixel(x, y);
+            let b = in2.pixel(x, y);
+
+            let qa = alpha(a);
+            let qb = alpha(b);
+
+            let mut px = [0u8; 4];
+            for c in 0..3 {
+                let ca = a[c] as f64 / 255.0;
+                let cb = b[c] as f64 / 255.0;
+                let cr = blend_channel(ca, cb, qa, qb, mode);
+                px[c] = (cr.max(0.0).min(1.0) * 255.0).round() as u8;
+            }
+
+            let qr = qa + qb - qa * qb;
+            px[3] = (qr.max(0.0).min(1.0) * 255.0).round() as u8;
+
+            out.set_pixel(x, y, px);
+        }
+    }
+
+    out
+}
+
+/// The W3C filter-effects `feBlend` formulas. `ca`/`cb` are premultiplied
+/// channel values (0-1) for `in`/`in2`, `qa`/`qb` their alphas; the result
+/// is premultiplied too.
+fn blend_channel(ca: f64, cb: f64, qa: f64, qb: f64, mode: tree::BlendMode) -> f64 {
+    match mode {
+        tree::BlendMode::Normal => (1.0 - qa) * cb + ca,
+        tree::BlendMode::Multiply => (1.0 - qa) * cb + (1.0 - qb) * ca + ca * cb,
+        tree::BlendMode::Screen => cb + ca - ca * cb,
+        tree::BlendMode::Darken => ((1.0 - qa) * cb + ca).min((1.0 - qb) * ca + cb),
+        tree::BlendMode::Lighten => ((1.0 - qa) * cb + ca).max((1.0 - qb) * ca + cb),
+    }
+}
+
+fn composite(in1: &Buffer, in2: &Buffer, operator: tree::CompositeOperator) -> Buffer {
+    let mut out = Buffer::blank(in1.width, in1.height, in1.stride);
+
+    for y in 0..in1.height {
+        for x in 0..in1.width {
+            let i1 = in1.pixel(x, y);
+            let i2 = in2.pixel(x, y);
+
+            let px = match operator {
+                tree::CompositeOperator::Over => porter_duff(i1, i2, 1.0, 1.0 - alpha(i1)),
+                tree::CompositeOperator::In => porter_duff(i1, i2, alpha(i2), 0.0),
+                tree::CompositeOperator::Out => porter_duff(i1, i2, 1.0 - alpha(i2), 0.0),
+                tree::CompositeOperator::Atop => porter_duff(i1, i2, alpha(i2), 1.0 - alpha(i1)),
+                tree::CompositeOperator::Xor => {
+                    porter_duff(i1, i2, 1.0 - alpha(i2), 1.0 - alpha(i1))
+                }
+                tree::CompositeOperator::Arithmetic { k1, k2, k3, k4 } => {
+                    arithmetic(i1, i2, k1, k2, k3, k4)
+                }
+            };
+
+            out.set_pixel(x, y, px);
+        }
+    }
+
+    out
+}
+
+fn alpha(px: [u8; 4]) -> f64 {
+    px[3] as f64 / 255.0
+}
+
+/// `result = i1 * k1 + i2 * k2`, all channels premultiplied.
+fn porter_duff(i1: [u8; 4], i2: [u8; 4], k1: f64, k2: f64) -> [u8; 4] {
+    let mut out = [0u8; 4];
+    for c in 0..4 {
+        let v = i1[c] as f64 * k1 + i2[c] as f64 * k2;
+        out[c] = v.round().max(0.0).min(255.0) as u8;
+    }
+    out
+}
+
+/// `result = k1*i1*i2 + k2*i1 + k3*i2 + k4`, all channels premultiplied.
+fn arithmetic(i1: [u8; 4], i2: [u8; 4], k1: f64, k2: f64, k3: f64, k4: f64) -> [u8; 4] {
+    let mut out = [0u8; 4];
+    for c in 0..4 {
+        let (a, b) = (i1[c] as f64 / 255.0, i2[c] as f64 / 255.0);
+        let v = k1 * a * b + k2 * a + k3 * b + k4;
+        out[c] = (v.max(0.0).min(1.0) * 255.0).round() as u8;
+    }
+    out
+}
@@ -0,0 +1,63 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Gradient paint-server rendering.
+//!
+//! This module also covers mesh (Coons-patch) gradients, built straight on
+//! top of `cairo::MeshPattern` — Cairo has no SVG-specific tessellation, so
+//! each `<meshpatch>` row/column maps onto one `begin_patch`/`end_patch` call.
+
+// external
+use cairo::{
+    self,
+    MatrixTrait,
+    PatternTrait,
+};
+
+// self
+use tree;
+
+/// Builds a `cairo::MeshPattern` for a mesh gradient's rows of patches.
+///
+/// Every patch is drawn from its own `top`/`right`/`bottom`/`left` sides and
+/// corner colors exactly as stored in `mesh`; a patch's top edge is not
+/// derived here from the previous row's bottom edge.
+pub fn prepare_mesh(mesh: &tree::MeshGradient) -> cairo::MeshPattern {
+    let pattern = cairo::MeshPattern::create();
+
+    for row in &mesh.rows {
+        for patch in &row.patches {
+            pattern.begin_patch();
+
+            draw_side(&pattern, &patch.top);
+            draw_side(&pattern, &patch.right);
+            draw_side(&pattern, &patch.bottom);
+            draw_side(&pattern, &patch.left);
+
+            for (idx, color) in patch.colors.iter().enumerate() {
+                pattern.set_corner_color_rgba(
+                    idx as u32, color.red, color.green, color.blue, color.alpha,
+                );
+            }
+
+            pattern.end_patch();
+        }
+    }
+
+    pattern
+}
+
+fn draw_side(pattern: &cairo::MeshPattern, side: &tree::MeshPatchSide) {
+    match *side {
+        tree::MeshPatchSide::Line(p) => {
+            pattern.line_to(p.x, p.y);
+        }
+        tree::MeshPatchSide::Curve(c1, c2, p) => {
+            pattern.curve_to(c1.x, c1.y, c2.x, c2.y, p.x, p.y);
+        }
+        tree::MeshPatchSide::Move(p) => {
+            pattern.move_to(p.x, p.y);
+        }
+    }
+}
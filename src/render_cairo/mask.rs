@@ -0,0 +1,144 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! SVG `<mask>` rendering.
+
+// external
+use cairo::{
+    self,
+    MatrixTrait,
+};
+
+// self
+use tree;
+use math::*;
+use traits::TransformFromBBox;
+use Options;
+use super::render_group;
+
+/// Renders `mask`'s children into a luminance alpha mask and returns it.
+///
+/// `group_surface` is the already-rendered (but not yet composited) content
+/// of the masked group; it's only used to size the returned surface.
+///
+/// `ambient_matrix` and `offset` must be the same ambient CTM and
+/// `bbox_to_device_rect` pixel offset used to build `group_surface` (the
+/// offset is `(0.0, 0.0)` when the group isn't bbox-cropped), so the mask's
+/// content lines up with it pixel-for-pixel instead of being drawn at the
+/// wrong position/scale whenever the ambient transform isn't identity (e.g.
+/// a fit-to `viewBox`, or a mask inside a nested, transformed group).
+pub fn apply(
+    rtree: &tree::RenderTree,
+    node: tree::NodeRef,
+    mask: &tree::Mask,
+    group_surface: &cairo::ImageSurface,
+    bbox: Rect,
+    img_size: Size,
+    ambient_matrix: &cairo::Matrix,
+    offset: (f64, f64),
+    opacity: Option<f64>,
+    opt: &Options,
+) -> Option<cairo::ImageSurface> {
+    let mask_surface = cairo::ImageSurface::create(
+        cairo::Format::ARgb32,
+        group_surface.get_width(),
+        group_surface.get_height(),
+    );
+
+    let mask_surface = match mask_surface {
+        Ok(surf) => surf,
+        Err(_) => {
+            warn!("Mask surface creation failed.");
+            return None;
+        }
+    };
+
+    {
+        let mask_cr = cairo::Context::new(&mask_surface);
+
+        // Same ambient CTM the masked group was drawn with, shifted by the
+        // same device-pixel offset its (possibly bbox-cropped) surface uses.
+        let mut matrix = *ambient_matrix;
+        matrix.x0 -= offset.0;
+        matrix.y0 -= offset.1;
+        mask_cr.set_matrix(matrix);
+
+        // Clip to the mask's own region (`maskUnits`/x/y/width/height)
+        // before switching to `maskContentUnits` for its content — the two
+        // are independent: a mask's content can be userSpaceOnUse while its
+        // region is still sized relative to the masked element's bbox.
+        let mask_region = region(mask, bbox);
+        mask_cr.rectangle(mask_region.x(), mask_region.y(), mask_region.width(), mask_region.height());
+        mask_cr.clip();
+
+        if mask.content_units_object_bbox {
+            mask_cr.transform(cairo::Matrix::from_bbox(bbox));
+        }
+
+        let matrix = mask_cr.get_matrix();
+        render_group(rtree, node, &mask_cr, &matrix, img_size, opt);
+    }
+
+    luminance_to_alpha(&mask_surface, opacity);
+
+    Some(mask_surface)
+}
+
+/// Resolves a `<mask>`'s own region (`maskUnits`/x/y/width/height), falling
+/// back to the spec default of `-10%/-10%/120%/120%` of `bbox` when the mask
+/// doesn't carry an explicit one.
+fn region(mask: &tree::Mask, bbox: Rect) -> Rect {
+    match mask.rect {
+        Some(r) if r.units_object_bbox => Rect::from_xywh(
+            bbox.x() + r.x * bbox.width(),
+            bbox.y() + r.y * bbox.height(),
+            r.width * bbox.width(),
+            r.height * bbox.height(),
+        ),
+        Some(r) => Rect::from_xywh(r.x, r.y, r.width, r.height),
+        None => {
+            let dx = bbox.width() * 0.1;
+            let dy = bbox.height() * 0.1;
+
+            Rect::from_xywh(
+                bbox.x() - dx,
+                bbox.y() - dy,
+                bbox.width() * 1.2,
+                bbox.height() * 1.2,
+            )
+        }
+    }
+}
+
+/// Converts an ARgb32 surface in-place from RGB luminance to an alpha-only
+/// mask, per the SVG `luminanceToAlpha` formula, optionally scaled by the
+/// masked group's own opacity.
+fn luminance_to_alpha(surface: &cairo::ImageSurface, opacity: Option<f64>) {
+    let opacity = opacity.unwrap_or(1.0);
+
+    surface.flush();
+
+    let stride = surface.get_stride() as usize;
+    let (w, h) = (surface.get_width() as usize, surface.get_height() as usize);
+
+    let mut data = surface.get_data().unwrap();
+    for y in 0..h {
+        for x in 0..w {
+            let idx = y * stride + x * 4;
+
+            // Premultiplied BGRA, as used internally by Cairo's ARgb32 format.
+            let b = data[idx + 0] as f64;
+            let g = data[idx + 1] as f64;
+            let r = data[idx + 2] as f64;
+
+            let luminance = 0.2125 * r + 0.7154 * g + 0.0721 * b;
+            let alpha = (luminance * opacity).min(255.0).max(0.0) as u8;
+
+            data[idx + 0] = 0;
+            data[idx + 1] = 0;
+            data[idx + 2] = 0;
+            data[idx + 3] = alpha;
+        }
+    }
+}
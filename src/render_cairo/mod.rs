@@ -5,6 +5,7 @@
 //! Cairo backend implementation.
 
 use std::f64;
+use std::io::Write;
 
 // external
 use cairo::{
@@ -34,8 +35,10 @@ use self::ext::*;
 mod clippath;
 mod ext;
 mod fill;
+mod filter;
 mod gradient;
 mod image;
+mod mask;
 mod path;
 mod pattern;
 mod stroke;
@@ -90,16 +93,95 @@ pub fn render_to_image(
         cr.paint();
     }
 
-    render_to_canvas(&cr, img_view, rtree);
+    render_to_canvas(&cr, img_view, rtree, opt);
 
     Ok(surface)
 }
 
+/// Renders SVG to a PDF document.
+///
+/// The whole document is rendered onto a single, appropriately sized page.
+pub fn render_to_pdf<W: Write>(
+    rtree: &tree::RenderTree,
+    opt: &Options,
+    writer: W,
+) -> Result<()> {
+    let img_size = render_utils::fit_to(rtree.svg_node().size, opt.fit_to);
+
+    debug_assert!(!img_size.is_empty_or_negative());
+
+    let surface = cairo::PdfSurface::for_stream(img_size.width, img_size.height, writer);
+    render_to_vector_surface(&surface, rtree, opt, img_size)
+}
+
+/// Renders SVG to a PostScript document.
+pub fn render_to_ps<W: Write>(
+    rtree: &tree::RenderTree,
+    opt: &Options,
+    writer: W,
+) -> Result<()> {
+    let img_size = render_utils::fit_to(rtree.svg_node().size, opt.fit_to);
+
+    debug_assert!(!img_size.is_empty_or_negative());
+
+    let surface = cairo::PsSurface::for_stream(img_size.width, img_size.height, writer);
+    render_to_vector_surface(&surface, rtree, opt, img_size)
+}
+
+/// Renders SVG to an EPS document.
+///
+/// Same as `render_to_ps`, but produces a single-page Encapsulated PostScript file.
+pub fn render_to_eps<W: Write>(
+    rtree: &tree::RenderTree,
+    opt: &Options,
+    writer: W,
+) -> Result<()> {
+    let img_size = render_utils::fit_to(rtree.svg_node().size, opt.fit_to);
+
+    debug_assert!(!img_size.is_empty_or_negative());
+
+    let surface = cairo::PsSurface::for_stream(img_size.width, img_size.height, writer);
+    if let Ok(ref surface) = surface {
+        surface.set_eps(true);
+    }
+
+    render_to_vector_surface(&surface, rtree, opt, img_size)
+}
+
+fn render_to_vector_surface<S: cairo::SurfaceExt>(
+    surface: &::std::result::Result<S, cairo::Status>,
+    rtree: &tree::RenderTree,
+    opt: &Options,
+    img_size: Size,
+) -> Result<()> {
+    let surface = match *surface {
+        Ok(ref v) => v,
+        Err(_) => {
+            return Err(ErrorKind::NoCanvas.into());
+        }
+    };
+
+    let img_view = Rect::new(Point::new(0.0, 0.0), img_size);
+    let cr = cairo::Context::new(surface);
+
+    if let Some(color) = opt.background {
+        cr.set_source_color(&color, 1.0);
+        cr.paint();
+    }
+
+    render_to_canvas(&cr, img_view, rtree, opt);
+
+    surface.finish();
+
+    Ok(())
+}
+
 /// Renders SVG to canvas.
 pub fn render_to_canvas(
     cr: &cairo::Context,
     img_view: Rect,
     rtree: &tree::RenderTree,
+    opt: &Options,
 ) {
     // Apply viewBox.
     let ts = {
@@ -109,7 +191,7 @@ pub fn render_to_canvas(
     };
     cr.transform(ts);
 
-    render_group(rtree, rtree.root(), &cr, &cr.get_matrix(), img_view.size);
+    render_group(rtree, rtree.root(), &cr, &cr.get_matrix(), img_view.size, opt);
 }
 
 fn render_group(
@@ -118,26 +200,17 @@ fn render_group(
     cr: &cairo::Context,
     matrix: &cairo::Matrix,
     img_size: Size,
+    opt: &Options,
 ) -> Rect {
     let mut g_bbox = Rect::from_xywh(f64::MAX, f64::MAX, 0.0, 0.0);
 
     for node in node.children() {
         cr.transform(node.transform().to_native());
 
-        let bbox = match *node.value() {
-            tree::NodeKind::Path(ref path) => {
-                Some(path::draw(rtree, path, cr))
-            }
-            tree::NodeKind::Text(_) => {
-                Some(text::draw(rtree, node, cr))
-            }
-            tree::NodeKind::Image(ref img) => {
-                Some(image::draw(img, cr))
-            }
-            tree::NodeKind::Group(ref g) => {
-                render_group_impl(rtree, node, g, cr, img_size)
-            }
-            _ => None,
+        let bbox = if let tree::NodeKind::Switch(_) = *node.value() {
+            render_switch(rtree, node, cr, matrix, img_size, opt)
+        } else {
+            render_node(rtree, node, cr, img_size, opt)
         };
 
         if let Some(bbox) = bbox {
@@ -150,20 +223,192 @@ fn render_group(
     g_bbox
 }
 
+fn render_node(
+    rtree: &tree::RenderTree,
+    node: tree::NodeRef,
+    cr: &cairo::Context,
+    img_size: Size,
+    opt: &Options,
+) -> Option<Rect> {
+    match *node.value() {
+        tree::NodeKind::Path(ref path) => {
+            Some(path::draw(rtree, path, cr))
+        }
+        tree::NodeKind::Text(_) => {
+            if opt.text_to_paths {
+                Some(text::draw_as_paths(rtree, node, cr))
+            } else {
+                Some(text::draw(rtree, node, cr))
+            }
+        }
+        tree::NodeKind::Image(ref img) => {
+            Some(image::draw(img, cr))
+        }
+        tree::NodeKind::Group(ref g) => {
+            render_group_impl(rtree, node, g, cr, img_size, opt)
+        }
+        _ => None,
+    }
+}
+
+/// Renders a `<switch>` element's conditionally-processed children.
+///
+/// Picks the first child, in document order, that passes conditional
+/// processing: one with no `systemLanguage` attribute passes trivially,
+/// otherwise it must have a case-insensitive prefix match against one of
+/// `opt.languages` (e.g. `en-US` matches `en`). Only the selected child is
+/// rendered, same as a real `<switch>`.
+fn render_switch(
+    rtree: &tree::RenderTree,
+    node: tree::NodeRef,
+    cr: &cairo::Context,
+    matrix: &cairo::Matrix,
+    img_size: Size,
+    opt: &Options,
+) -> Option<Rect> {
+    let selected = node.children()
+        .find(|child| {
+            match child.system_language() {
+                Some(langs) => langs.iter().any(|lang| matches_language(lang, &opt.languages)),
+                None => true,
+            }
+        })?;
+
+    cr.transform(selected.transform().to_native());
+    let bbox = render_node(rtree, selected, cr, img_size, opt);
+    cr.set_matrix(*matrix);
+
+    bbox
+}
+
+fn matches_language(lang: &str, accept: &[String]) -> bool {
+    accept.iter().any(|accepted| {
+        lang.eq_ignore_ascii_case(accepted)
+            || lang.to_ascii_lowercase().starts_with(&format!("{}-", accepted.to_ascii_lowercase()))
+    })
+}
+
+/// Whether `g` needs a temporary surface at all. A group with none of these
+/// properties set can be drawn straight onto its parent's context, same as
+/// librsvg does for non-isolated groups.
+fn needs_isolation(g: &tree::Group) -> bool {
+    g.opacity.is_some() || g.clip_path.is_some() || g.mask.is_some() || g.filter.is_some()
+}
+
+/// Converts a user-space bbox into an integer device-pixel rect, clamped to
+/// the canvas bounds, so the isolation surface only covers the pixels the
+/// group can actually touch instead of the whole `img_size` canvas.
+fn bbox_to_device_rect(bbox: Rect, matrix: &cairo::Matrix, img_size: Size) -> (i32, i32, i32, i32) {
+    let corners = [
+        (bbox.x(), bbox.y()),
+        (bbox.x() + bbox.width(), bbox.y()),
+        (bbox.x(), bbox.y() + bbox.height()),
+        (bbox.x() + bbox.width(), bbox.y() + bbox.height()),
+    ];
+
+    let mut min_x = f64::MAX;
+    let mut min_y = f64::MAX;
+    let mut max_x = f64::MIN;
+    let mut max_y = f64::MIN;
+
+    for &(x, y) in &corners {
+        let (dx, dy) = matrix.transform_point(x, y);
+        min_x = min_x.min(dx);
+        min_y = min_y.min(dy);
+        max_x = max_x.max(dx);
+        max_y = max_y.max(dy);
+    }
+
+    let x = min_x.floor().max(0.0);
+    let y = min_y.floor().max(0.0);
+    let x2 = max_x.ceil().min(img_size.width);
+    let y2 = max_y.ceil().min(img_size.height);
+
+    let w = (x2 - x).max(1.0);
+    let h = (y2 - y).max(1.0);
+
+    (x as i32, y as i32, w as i32, h as i32)
+}
+
 fn render_group_impl(
     rtree: &tree::RenderTree,
     node: tree::NodeRef,
     g: &tree::Group,
     cr: &cairo::Context,
     img_size: Size,
+    opt: &Options,
 ) -> Option<Rect> {
-    let sub_surface = cairo::ImageSurface::create(
-        cairo::Format::ARgb32,
-        img_size.width as i32,
-        img_size.height as i32
-    );
+    if !needs_isolation(g) {
+        return Some(render_group(rtree, node, cr, &cr.get_matrix(), img_size, opt));
+    }
+
+    // The group's final bbox is only known after drawing it, so defer
+    // rasterization to a recording surface first; it's cheap to create and
+    // lets us size the real isolation surface to just the group's bbox
+    // instead of the whole canvas.
+    let extents = cairo::Rectangle {
+        x: 0.0, y: 0.0, width: img_size.width, height: img_size.height,
+    };
+    let recording = match cairo::RecordingSurface::create(cairo::Content::ColorAlpha, Some(extents)) {
+        Ok(surf) => surf,
+        Err(_) => {
+            warn!("Subsurface creation failed.");
+            return None;
+        }
+    };
+
+    let rec_cr = cairo::Context::new(&recording);
+    rec_cr.set_matrix(cr.get_matrix());
+
+    let bbox = render_group(rtree, node, &rec_cr, &cr.get_matrix(), img_size, opt);
+
+    let curr_matrix = cr.get_matrix();
+
+    // Masks and filters need read access to rendered pixels, which a
+    // recording surface can't provide, so only they pay the rasterization
+    // cost, and only for the bbox they actually cover.
+    if g.mask.is_none() && g.filter.is_none() {
+        if let Some(idx) = g.clip_path {
+            let clip_node = rtree.defs_at(idx);
+            if let tree::NodeKind::ClipPath(ref cp) = *clip_node.value() {
+                clippath::apply(rtree, clip_node, cp, &rec_cr, bbox, img_size);
+            }
+        }
+
+        cr.set_matrix(cairo::Matrix::identity());
+        cr.set_source_surface(&recording, 0.0, 0.0);
+
+        if let Some(opacity) = g.opacity {
+            cr.paint_with_alpha(opacity);
+        } else {
+            cr.paint();
+        }
+
+        cr.set_matrix(curr_matrix);
+
+        return Some(bbox);
+    }
+
+    // A filter's region (by default -10%/-10%/120%/120% of the bbox, per the
+    // SVG spec, but overridable via the filter's own x/y/width/height) can
+    // extend past the element's own bbox, e.g. a blur or an offset
+    // primitive — so a filtered group's isolation surface must cover that
+    // region, not just the unfiltered bbox.
+    let filter_node = g.filter.map(|idx| rtree.defs_at(idx));
+
+    let region_bbox = match filter_node {
+        Some(ref fnode) => {
+            match *fnode.value() {
+                tree::NodeKind::Filter(ref fltr) => filter::region(bbox, fltr),
+                _ => bbox,
+            }
+        }
+        None => bbox,
+    };
 
-    let sub_surface = match sub_surface {
+    let (bx, by, bw, bh) = bbox_to_device_rect(region_bbox, &curr_matrix, img_size);
+
+    let image = match cairo::ImageSurface::create(cairo::Format::ARgb32, bw, bh) {
         Ok(surf) => surf,
         Err(_) => {
             warn!("Subsurface creation failed.");
@@ -171,24 +416,36 @@ fn render_group_impl(
         }
     };
 
-    let sub_cr = cairo::Context::new(&sub_surface);
-    sub_cr.set_matrix(cr.get_matrix());
+    let image_cr = cairo::Context::new(&image);
+    image_cr.translate(-(bx as f64), -(by as f64));
+    image_cr.set_source_surface(&recording, 0.0, 0.0);
+    image_cr.paint();
 
-    let bbox = render_group(rtree, node, &sub_cr, &cr.get_matrix(), img_size);
+    if let Some(ref fnode) = filter_node {
+        if let tree::NodeKind::Filter(ref fltr) = *fnode.value() {
+            filter::apply(fltr, &image, &curr_matrix);
+        }
+    }
 
     if let Some(idx) = g.clip_path {
         let clip_node = rtree.defs_at(idx);
         if let tree::NodeKind::ClipPath(ref cp) = *clip_node.value() {
-            clippath::apply(rtree, clip_node, cp, &sub_cr, bbox, img_size);
+            clippath::apply(rtree, clip_node, cp, &image_cr, bbox, img_size);
         }
     }
 
-    let curr_matrix = cr.get_matrix();
     cr.set_matrix(cairo::Matrix::identity());
-
-    cr.set_source_surface(&sub_surface, 0.0, 0.0);
-
-    if let Some(opacity) = g.opacity {
+    cr.set_source_surface(&image, bx as f64, by as f64);
+
+    if let Some(idx) = g.mask {
+        let mask_node = rtree.defs_at(idx);
+        if let tree::NodeKind::Mask(ref mask) = *mask_node.value() {
+            let offset = (bx as f64, by as f64);
+            if let Some(lum_surface) = mask::apply(rtree, mask_node, mask, &image, bbox, img_size, &curr_matrix, offset, g.opacity, opt) {
+                cr.mask_surface(&lum_surface, bx as f64, by as f64);
+            }
+        }
+    } else if let Some(opacity) = g.opacity {
         cr.paint_with_alpha(opacity);
     } else {
         cr.paint();
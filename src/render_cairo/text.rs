@@ -0,0 +1,104 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Text rendering.
+//!
+//! Text is normally shown with Cairo's native glyph API (`draw`), which
+//! depends on the fonts installed on the machine doing the rendering. For
+//! output that must stay pixel-identical regardless of the installed fonts
+//! (and to make PDF/PS export self-contained), `draw_as_paths` converts each
+//! glyph to its outline and feeds it through the same fill/stroke pipeline
+//! used for `<path>` elements.
+
+// external
+use cairo;
+
+// self
+use tree;
+use math::*;
+use super::fill;
+use super::path;
+
+/// Renders a text node's glyphs using Cairo's native text API.
+pub fn draw(
+    rtree: &tree::RenderTree,
+    node: tree::NodeRef,
+    cr: &cairo::Context,
+) -> Rect {
+    draw_chunks(rtree, node, cr, draw_glyph_run)
+}
+
+/// Renders a text node by converting its glyphs to outlines and filling /
+/// stroking them exactly like a `<path>`, instead of showing native text.
+pub fn draw_as_paths(
+    rtree: &tree::RenderTree,
+    node: tree::NodeRef,
+    cr: &cairo::Context,
+) -> Rect {
+    draw_chunks(rtree, node, cr, draw_glyph_run_as_paths)
+}
+
+fn draw_chunks<F>(
+    rtree: &tree::RenderTree,
+    node: tree::NodeRef,
+    cr: &cairo::Context,
+    draw_run: F,
+) -> Rect
+    where F: Fn(&tree::RenderTree, &tree::TextChunk, &cairo::Context) -> Rect
+{
+    let text = match *node.value() {
+        tree::NodeKind::Text(ref text) => text,
+        _ => unreachable!(),
+    };
+
+    let mut bbox = Rect::from_xywh(::std::f64::MAX, ::std::f64::MAX, 0.0, 0.0);
+    for chunk in &text.chunks {
+        bbox.expand_from_rect(draw_run(rtree, chunk, cr));
+    }
+
+    bbox
+}
+
+fn draw_glyph_run(
+    rtree: &tree::RenderTree,
+    chunk: &tree::TextChunk,
+    cr: &cairo::Context,
+) -> Rect {
+    cr.set_font_face(&chunk.font.to_cairo_face());
+    cr.set_font_size(chunk.font.size);
+    cr.show_text(&chunk.text);
+
+    chunk.bbox
+}
+
+fn draw_glyph_run_as_paths(
+    rtree: &tree::RenderTree,
+    chunk: &tree::TextChunk,
+    cr: &cairo::Context,
+) -> Rect {
+    cr.set_font_face(&chunk.font.to_cairo_face());
+    cr.set_font_size(chunk.font.size);
+
+    // Converting glyphs to outlines with `text_path` draws them into the
+    // current path instead of showing them, so the usual `path::fill`/
+    // `path::stroke` machinery takes over from there, exactly as it would
+    // for a plain `<path>` element.
+    cr.text_path(&chunk.text);
+
+    // `path::fill` doesn't know about mesh gradients (they're resolved in
+    // `fill`, not in the generic paint-server dispatch it shares with
+    // `<path>`), so set the source ourselves first for that one case; a
+    // text chunk's bbox is already known here, unlike a `<path>`'s, which
+    // isn't resolved until `path::fill` walks its geometry.
+    if let Some(ref chunk_fill) = chunk.fill {
+        if let tree::PaintServer::MeshGradient(_) = chunk_fill.paint {
+            fill::set_source_paint_server(cr, &chunk_fill.paint, chunk.bbox);
+        }
+    }
+
+    let bbox = path::fill(rtree, &chunk.fill, cr);
+    path::stroke(rtree, &chunk.stroke, cr);
+
+    bbox
+}